@@ -0,0 +1,39 @@
+use crate::yahoo::CachedQuote;
+use log::warn;
+use std::collections::HashMap;
+
+const CACHE_FILE: &str = ".ssg-cache.json";
+
+#[derive(serde::Serialize, serde::Deserialize, Default)]
+pub(crate) struct Cache {
+    #[serde(default)]
+    quotes: HashMap<String, CachedQuote>,
+}
+
+impl Cache {
+    pub(crate) fn load() -> Self {
+        std::fs::read_to_string(CACHE_FILE)
+            .ok()
+            .and_then(|data| serde_json::from_str(&data).ok())
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn save(&self) {
+        match serde_json::to_string_pretty(self) {
+            Ok(data) => {
+                if let Err(err) = std::fs::write(CACHE_FILE, data) {
+                    warn!("Failed to write quote cache `{}`: {}", CACHE_FILE, err);
+                }
+            }
+            Err(err) => warn!("Failed to serialize quote cache: {}", err),
+        }
+    }
+
+    pub(crate) fn get(&self, ticker: &str) -> Option<CachedQuote> {
+        self.quotes.get(ticker).cloned()
+    }
+
+    pub(crate) fn insert(&mut self, ticker: String, quote: CachedQuote) {
+        self.quotes.insert(ticker, quote);
+    }
+}
@@ -1,15 +1,71 @@
+mod cache;
 mod config;
 mod yahoo;
 
 use clap::{value_parser, ArgAction};
-use log::{debug, error};
+use log::{debug, error, warn};
 use rust_decimal::prelude::*;
+use std::collections::HashMap;
 use std::io::Read;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use yahoo::Quote;
 
 const USER_AGENT: &'static str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
 
+/// How the fetched quotes are written out.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Csv,
+    Json,
+    Table,
+}
+
+impl OutputFormat {
+    fn parse(value: &str) -> Self {
+        match value {
+            "json" => OutputFormat::Json,
+            "table" => OutputFormat::Table,
+            _ => OutputFormat::Csv,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::Json => "json",
+            OutputFormat::Table => "txt",
+        }
+    }
+}
+
+/// Limits calls to at most one per `interval`, shared across a worker pool.
+struct RateLimiter {
+    interval: Duration,
+    last_call: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_call: Mutex::new(Instant::now() - interval),
+        }
+    }
+
+    /// Blocks the calling thread until `interval` has elapsed since the last permitted call.
+    fn wait(&self) {
+        let mut last_call = self.last_call.lock().unwrap();
+        let sleep_until = *last_call + self.interval;
+        let now = Instant::now();
+        if let Some(remaining) = sleep_until.checked_duration_since(now) {
+            std::thread::sleep(remaining);
+        }
+        *last_call = Instant::now();
+    }
+}
+
 fn main() -> Result<(), Error> {
     env_logger::init();
     let matches = clap::Command::new("Stock Spreadsheet Generator")
@@ -39,12 +95,100 @@ fn main() -> Result<(), Error> {
                 .action(ArgAction::SetTrue)
                 .help("disable opening the generated csv automatically"),
         )
+        .arg(
+            clap::Arg::new("history")
+                .long("history")
+                .action(ArgAction::SetTrue)
+                .help("fetch historical OHLCV candles instead of a current-price snapshot"),
+        )
+        .arg(
+            clap::Arg::new("interval")
+                .long("interval")
+                .value_name("INTERVAL")
+                .help("candle interval for --history, e.g. 1m, 1d, 1wk")
+                .default_value("1d"),
+        )
+        .arg(
+            clap::Arg::new("range")
+                .long("range")
+                .value_name("RANGE")
+                .help("how far back to fetch candles for --history, e.g. 5d, 1mo, 1y")
+                .default_value("1mo"),
+        )
+        .arg(
+            clap::Arg::new("concurrency")
+                .long("concurrency")
+                .short('c')
+                .value_name("WORKERS")
+                .value_parser(value_parser!(u16))
+                .help("number of tickers to fetch in parallel")
+                .default_value("4"),
+        )
+        .arg(
+            clap::Arg::new("timeout")
+                .long("timeout")
+                .value_name("ms")
+                .value_parser(value_parser!(u64))
+                .help("http request timeout (in ms)")
+                .default_value("10000"),
+        )
+        .arg(
+            clap::Arg::new("retries")
+                .long("retries")
+                .value_name("COUNT")
+                .value_parser(value_parser!(u32))
+                .help("number of times to retry a failed request, with exponential backoff")
+                .default_value("3"),
+        )
+        .arg(
+            clap::Arg::new("cache-ttl")
+                .long("cache-ttl")
+                .value_name("s")
+                .value_parser(value_parser!(u64))
+                .help("reuse a cached quote younger than this many seconds instead of re-fetching it")
+                .default_value("0"),
+        )
+        .arg(
+            clap::Arg::new("format")
+                .long("format")
+                .value_name("FORMAT")
+                .value_parser(["csv", "json", "table"])
+                .help("output format: csv, json, or an aligned table printed to stdout")
+                .default_value("csv"),
+        )
+        .arg(
+            clap::Arg::new("base-currency")
+                .long("base-currency")
+                .value_name("CURRENCY")
+                .help("currency each position is converted into before summing the portfolio total")
+                .default_value("USD"),
+        )
         .get_matches();
 
     let filename: &String = matches.get_one("config").expect("missing argument: config");
     let delay: u16 = *matches.get_one("delay").expect("missing argument: delay");
     let delay = std::time::Duration::from_millis(delay as u64);
     let open: bool = !matches.get_flag("no-open");
+    let history: bool = matches.get_flag("history");
+    let interval: &String = matches.get_one("interval").expect("missing argument: interval");
+    let range: &String = matches.get_one("range").expect("missing argument: range");
+    let concurrency: u16 = *matches
+        .get_one("concurrency")
+        .expect("missing argument: concurrency");
+    let timeout: u64 = *matches.get_one("timeout").expect("missing argument: timeout");
+    let retries: u32 = *matches.get_one("retries").expect("missing argument: retries");
+    let cache_ttl: u64 = *matches
+        .get_one("cache-ttl")
+        .expect("missing argument: cache-ttl");
+    let cache_ttl = Duration::from_secs(cache_ttl);
+    let format = OutputFormat::parse(
+        matches
+            .get_one::<String>("format")
+            .expect("missing argument: format"),
+    );
+    let base_currency: &String = matches
+        .get_one("base-currency")
+        .expect("missing argument: base-currency");
 
     debug!(
         "config: `{}`, delay: {}, open: {}",
@@ -55,42 +199,167 @@ fn main() -> Result<(), Error> {
 
     let config = config::load_from_file(filename);
 
-    let mut client = reqwest::blocking::Client::builder()
+    // TLS backend (default-tls / rustls-tls-webpki-roots / rustls-tls-native-roots) is picked
+    // via Cargo features on the `reqwest` dependency, not at runtime.
+    let client = reqwest::blocking::Client::builder()
         .user_agent(USER_AGENT)
+        .timeout(Duration::from_millis(timeout))
         .build()
         .unwrap();
 
-    let mut quotes: Vec<(config::Ticker, Option<Quote>)> = Vec::new();
-
-    let mut last_call: Option<Instant> = None;
+    // History mode always writes CSV; `--format` only selects between csv/json/table for a
+    // quote snapshot.
+    let written_format = if history { OutputFormat::Csv } else { format };
+    if history && format != OutputFormat::Csv {
+        warn!("--format is ignored with --history, which always writes CSV");
+    }
 
-    for ticker in config.tickers {
-        if let Some(instant) = last_call {
-            std::thread::sleep(
-                delay
-                    - instant
-                        .checked_duration_since(Instant::now())
-                        .unwrap_or(Duration::from_millis(0)),
-            );
+    // `config.output_file` predates --format and is usually still "output.csv", so substitute
+    // whatever extension written_format actually produces rather than trusting the stored one.
+    let output_filename = match &config.output_file {
+        Some(output_file) => {
+            let stem = output_file
+                .rsplit_once('.')
+                .map(|(stem, _)| stem)
+                .unwrap_or(output_file);
+            format!("{stem}.{}", written_format.extension())
         }
+        None => format!(
+            "{}.{}",
+            filename.strip_suffix(".toml").unwrap_or(filename),
+            written_format.extension()
+        ),
+    };
 
-        match yahoo::request_quote(&mut client, &ticker.id) {
-            Ok(quote) => quotes.push((ticker, Some(quote))),
-            Err(err) => {
-                error!("Failed to get ticker {}: {}", &ticker.id, err);
-                quotes.push((ticker, None));
-            }
-        };
-        last_call = Some(Instant::now());
+    let limiter = Arc::new(RateLimiter::new(delay));
+    let tickers = config.tickers;
+    let ticker_count = tickers.len();
+
+    let (task_tx, task_rx) = mpsc::channel::<(usize, config::Ticker)>();
+    for task in tickers.into_iter().enumerate() {
+        task_tx.send(task).expect("failed to queue ticker");
     }
+    drop(task_tx);
+    let task_rx = Arc::new(Mutex::new(task_rx));
+
+    if history {
+        let (result_tx, result_rx) =
+            mpsc::channel::<(usize, config::Ticker, Result<Vec<yahoo::Candle>, String>)>();
+
+        let mut workers = Vec::new();
+        for _ in 0..concurrency.max(1) {
+            let task_rx = Arc::clone(&task_rx);
+            let result_tx = result_tx.clone();
+            let limiter = Arc::clone(&limiter);
+            let mut client = client.clone();
+            let interval = interval.clone();
+            let range = range.clone();
+            workers.push(std::thread::spawn(move || loop {
+                let Ok((index, ticker)) = task_rx.lock().unwrap().recv() else {
+                    break;
+                };
+                limiter.wait();
+                let result = match yahoo::request_history(
+                    &mut client,
+                    &ticker.id,
+                    &interval,
+                    &range,
+                    retries,
+                ) {
+                    Ok(candles) => Ok(candles),
+                    Err(err) => {
+                        error!("Failed to get history for {}: {}", &ticker.id, err);
+                        Err(err.to_string())
+                    }
+                };
+                if result_tx.send((index, ticker, result)).is_err() {
+                    break;
+                }
+            }));
+        }
+        drop(result_tx);
+
+        let mut histories: Vec<Option<(config::Ticker, Result<Vec<yahoo::Candle>, String>)>> =
+            (0..ticker_count).map(|_| None).collect();
+        for (index, ticker, result) in result_rx {
+            histories[index] = Some((ticker, result));
+        }
+        for worker in workers {
+            worker.join().expect("history worker thread panicked");
+        }
+
+        let histories: Vec<(config::Ticker, Result<Vec<yahoo::Candle>, String>)> =
+            histories.into_iter().map(|entry| entry.unwrap()).collect();
+        write_history_csv(&histories, &output_filename)?;
+    } else {
+        let cache = Arc::new(Mutex::new(cache::Cache::load()));
+        let (result_tx, result_rx) =
+            mpsc::channel::<(usize, config::Ticker, Result<yahoo::CachedQuote, String>)>();
+
+        let mut workers = Vec::new();
+        for _ in 0..concurrency.max(1) {
+            let task_rx = Arc::clone(&task_rx);
+            let result_tx = result_tx.clone();
+            let limiter = Arc::clone(&limiter);
+            let cache = Arc::clone(&cache);
+            let mut client = client.clone();
+            workers.push(std::thread::spawn(move || loop {
+                let Ok((index, ticker)) = task_rx.lock().unwrap().recv() else {
+                    break;
+                };
+                let cached = cache.lock().unwrap().get(&ticker.id);
+                let fresh = cached
+                    .as_ref()
+                    .is_some_and(|cached| yahoo::is_fresh(cached, cache_ttl));
+                if !fresh {
+                    limiter.wait();
+                }
+                let result =
+                    match yahoo::request_quote(&mut client, &ticker.id, retries, cached, cache_ttl)
+                    {
+                        Ok(quote) => {
+                            cache.lock().unwrap().insert(ticker.id.clone(), quote.clone());
+                            Ok(quote)
+                        }
+                        Err(err) => {
+                            error!("Failed to get ticker {}: {}", &ticker.id, err);
+                            Err(err.to_string())
+                        }
+                    };
+                if result_tx.send((index, ticker, result)).is_err() {
+                    break;
+                }
+            }));
+        }
+        drop(result_tx);
+
+        let mut quotes: Vec<Option<(config::Ticker, Result<yahoo::CachedQuote, String>)>> =
+            (0..ticker_count).map(|_| None).collect();
+        for (index, ticker, result) in result_rx {
+            quotes[index] = Some((ticker, result));
+        }
+        for worker in workers {
+            worker.join().expect("quote worker thread panicked");
+        }
+        Arc::try_unwrap(cache)
+            .expect("all worker threads have finished")
+            .into_inner()
+            .unwrap()
+            .save();
+
+        let quotes: Vec<(config::Ticker, Result<Quote, String>)> = quotes
+            .into_iter()
+            .map(|entry| entry.unwrap())
+            .map(|(ticker, cached)| (ticker, cached.map(|cached| cached.quote)))
+            .collect();
 
-    let output_filename = config
-        .output_file
-        .unwrap_or_else(|| format!("{}.csv", filename.strip_suffix(".toml").unwrap_or(filename)));
+        let mut client = client.clone();
+        let rates = resolve_fx_rates(&mut client, &limiter, &quotes, base_currency, retries);
 
-    write_csv(&mut quotes, &output_filename)?;
+        write_output(&quotes, &rates, base_currency, format, &output_filename)?;
+    }
 
-    if open {
+    if open && written_format != OutputFormat::Table {
         if let Err(error) = open::that(output_filename) {
             log::error!("Failed to open output file! {}", error);
             std::io::stdin()
@@ -102,17 +371,87 @@ fn main() -> Result<(), Error> {
     Ok(())
 }
 
+/// Resolves an FX rate for every distinct currency among `quotes` (besides `base_currency`)
+/// via a synthetic `{currency}{base}=X` symbol, e.g. `EURUSD=X`. A currency whose rate can't
+/// be resolved is absent from the returned map.
+fn resolve_fx_rates(
+    client: &mut reqwest::blocking::Client,
+    limiter: &RateLimiter,
+    quotes: &Vec<(config::Ticker, Result<Quote, String>)>,
+    base_currency: &str,
+    retries: u32,
+) -> HashMap<String, Decimal> {
+    let mut rates = HashMap::new();
+    rates.insert(base_currency.to_string(), Decimal::one());
+
+    let mut currencies: Vec<&String> = quotes
+        .iter()
+        .filter_map(|(_, quote)| quote.as_ref().ok())
+        .map(|quote| &quote.currency)
+        .collect();
+    currencies.sort();
+    currencies.dedup();
+
+    for currency in currencies {
+        if currency == base_currency {
+            continue;
+        }
+        let symbol = format!("{currency}{base_currency}=X");
+        limiter.wait();
+        match yahoo::request_quote(client, &symbol, retries, None, Duration::ZERO) {
+            Ok(cached) => {
+                rates.insert(currency.clone(), cached.quote.price);
+            }
+            Err(err) => {
+                warn!(
+                    "Failed to resolve FX rate `{}` ({} -> {}), positions in {} will be excluded from the total: {}",
+                    symbol, currency, base_currency, currency, err
+                );
+            }
+        }
+    }
+
+    rates
+}
+
+fn write_output(
+    quotes: &Vec<(config::Ticker, Result<Quote, String>)>,
+    rates: &HashMap<String, Decimal>,
+    base_currency: &str,
+    format: OutputFormat,
+    output_filename: &str,
+) -> Result<(), Error> {
+    match format {
+        OutputFormat::Csv => write_csv(quotes, rates, base_currency, output_filename),
+        OutputFormat::Json => write_json(quotes, rates, base_currency, output_filename),
+        OutputFormat::Table => {
+            print_table(quotes, rates, base_currency);
+            Ok(())
+        }
+    }
+}
+
 fn write_csv(
-    quotes: &Vec<(config::Ticker, Option<Quote>)>,
+    quotes: &Vec<(config::Ticker, Result<Quote, String>)>,
+    rates: &HashMap<String, Decimal>,
+    base_currency: &str,
     output_filename: &str,
 ) -> Result<(), Error> {
     let mut writer = csv::Writer::from_path(&output_filename)?;
     let mut total = Decimal::zero();
-    writer.write_record(&["Ticker", "Price", "Change", "Quantity", "Total", "Currency"])?;
+    writer.write_record(&[
+        "Ticker",
+        "Price",
+        "Change",
+        "Quantity",
+        "Total",
+        "Currency",
+        &format!("Total ({base_currency})"),
+    ])?;
     for (ticker, quote) in quotes {
         let quantity = ticker.quantity.unwrap_or(0);
         match quote {
-            Some(quote) => {
+            Ok(quote) => {
                 writer.write_field(&ticker.id)?;
                 writer.write_field(quote.price.round_dp(2).to_string())?;
                 writer.write_field(quote.change.round_dp(2).to_string())?;
@@ -123,11 +462,19 @@ fn write_csv(
                         .to_string(),
                 )?;
                 writer.write_field(&quote.currency)?;
-                writer.write_record(None::<&[u8]>)?;
 
-                total += quote.price;
+                match rates.get(&quote.currency) {
+                    Some(rate) => {
+                        let base_total =
+                            (quote.price * Decimal::from(quantity) * rate).round_dp(2);
+                        writer.write_field(base_total.to_string())?;
+                        total += base_total;
+                    }
+                    None => writer.write_field("")?,
+                }
+                writer.write_record(None::<&[u8]>)?;
             }
-            None => {
+            Err(_) => {
                 writer.write_record(&[
                     &ticker.id,
                     "",
@@ -135,27 +482,198 @@ fn write_csv(
                     quantity.to_string().as_str(),
                     "",
                     "",
+                    "",
                 ])?;
             }
         }
     }
 
     writer
-        .write_record(["", "", "", "", "", ""])
+        .write_record(["", "", "", "", "", "", ""])
         .expect("Failed to write newline in csv!");
 
     writer
-        .write_record(["", "", "", "", &total.to_string(), ""])
+        .write_record(["", "", "", "", "", "", &total.to_string()])
         .expect("Failed to write total!");
 
     writer.flush()?;
     Ok(())
 }
 
+fn write_json(
+    quotes: &Vec<(config::Ticker, Result<Quote, String>)>,
+    rates: &HashMap<String, Decimal>,
+    base_currency: &str,
+    output_filename: &str,
+) -> Result<(), Error> {
+    let mut total = Decimal::zero();
+    let rows: Vec<serde_json::Value> = quotes
+        .iter()
+        .map(|(ticker, quote)| {
+            let quantity = ticker.quantity.unwrap_or(0);
+            match quote {
+                Ok(quote) => {
+                    let base_total = rates.get(&quote.currency).map(|rate| {
+                        let base_total = (quote.price * Decimal::from(quantity) * rate).round_dp(2);
+                        total += base_total;
+                        base_total
+                    });
+                    serde_json::json!({
+                        "ticker": ticker.id,
+                        "price": quote.price,
+                        "change": quote.change,
+                        "quantity": quantity,
+                        "total": (quote.price * Decimal::from(quantity)).round_dp(2),
+                        "currency": quote.currency,
+                        "totalBaseCurrency": base_total,
+                    })
+                }
+                Err(err) => serde_json::json!({
+                    "ticker": ticker.id,
+                    "quantity": quantity,
+                    "error": err,
+                }),
+            }
+        })
+        .collect();
+
+    let document = serde_json::json!({
+        "quotes": rows,
+        "baseCurrency": base_currency,
+        "total": total.round_dp(2),
+    });
+
+    std::fs::write(output_filename, serde_json::to_string_pretty(&document)?)
+        .map_err(Error::IoError)?;
+    Ok(())
+}
+
+/// Renders an aligned, column-padded table straight to stdout.
+fn print_table(
+    quotes: &Vec<(config::Ticker, Result<Quote, String>)>,
+    rates: &HashMap<String, Decimal>,
+    base_currency: &str,
+) {
+    let headers: [String; 8] = [
+        "Ticker".to_string(),
+        "Price".to_string(),
+        "Change".to_string(),
+        "Quantity".to_string(),
+        "Total".to_string(),
+        "Currency".to_string(),
+        format!("Total ({base_currency})"),
+        "Error".to_string(),
+    ];
+
+    let mut total = Decimal::zero();
+    let rows: Vec<[String; 8]> = quotes
+        .iter()
+        .map(|(ticker, quote)| {
+            let quantity = ticker.quantity.unwrap_or(0);
+            match quote {
+                Ok(quote) => {
+                    let base_total = rates.get(&quote.currency).map(|rate| {
+                        let base_total = (quote.price * Decimal::from(quantity) * rate).round_dp(2);
+                        total += base_total;
+                        base_total
+                    });
+                    [
+                        ticker.id.clone(),
+                        quote.price.round_dp(2).to_string(),
+                        quote.change.round_dp(2).to_string(),
+                        quantity.to_string(),
+                        (quote.price * Decimal::from(quantity))
+                            .round_dp(2)
+                            .to_string(),
+                        quote.currency.clone(),
+                        base_total.map(|total| total.to_string()).unwrap_or_default(),
+                        String::new(),
+                    ]
+                }
+                Err(err) => [
+                    ticker.id.clone(),
+                    String::new(),
+                    String::new(),
+                    quantity.to_string(),
+                    String::new(),
+                    String::new(),
+                    String::new(),
+                    err.clone(),
+                ],
+            }
+        })
+        .collect();
+
+    let mut widths = headers.clone().map(|header| header.len());
+    for row in &rows {
+        for (width, field) in widths.iter_mut().zip(row) {
+            *width = (*width).max(field.len());
+        }
+    }
+
+    let print_row = |fields: &[String; 8]| {
+        let padded: Vec<String> = fields
+            .iter()
+            .zip(widths)
+            .map(|(field, width)| format!("{:<width$}", field, width = width))
+            .collect();
+        println!("{}", padded.join(" | "));
+    };
+
+    print_row(&headers);
+    println!(
+        "{}",
+        widths
+            .iter()
+            .map(|width| "-".repeat(*width))
+            .collect::<Vec<_>>()
+            .join("-+-")
+    );
+    for row in &rows {
+        print_row(row);
+    }
+    println!("Total ({}): {}", base_currency, total.round_dp(2));
+}
+
+fn write_history_csv(
+    histories: &Vec<(config::Ticker, Result<Vec<yahoo::Candle>, String>)>,
+    output_filename: &str,
+) -> Result<(), Error> {
+    let mut writer = csv::Writer::from_path(&output_filename)?;
+    writer.write_record(["Ticker", "Time", "Open", "High", "Low", "Close", "Volume", "Error"])?;
+    for (ticker, candles) in histories {
+        match candles {
+            Ok(candles) => {
+                for candle in candles {
+                    writer.write_field(&ticker.id)?;
+                    writer.write_field(candle.timestamp.to_rfc3339())?;
+                    writer.write_field(candle.open.round_dp(2).to_string())?;
+                    writer.write_field(candle.high.round_dp(2).to_string())?;
+                    writer.write_field(candle.low.round_dp(2).to_string())?;
+                    writer.write_field(candle.close.round_dp(2).to_string())?;
+                    writer.write_field(candle.volume.to_string())?;
+                    writer.write_field("")?;
+                    writer.write_record(None::<&[u8]>)?;
+                }
+            }
+            Err(err) => {
+                writer.write_record([&ticker.id, "", "", "", "", "", "", err])?;
+            }
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
 #[derive(thiserror::Error, Debug)]
 enum Error {
     #[error("failed to write csv data: {0}")]
     CsvError(#[from] csv::Error),
+    #[error("failed to serialize json: {0}")]
+    JsonError(#[from] serde_json::Error),
+    #[error("failed to write output file: {0}")]
+    IoError(std::io::Error),
     #[error("failed to open file with default program: {0}")]
-    OpeningError(#[from] std::io::Error),
+    OpeningError(std::io::Error),
 }
@@ -1,4 +1,7 @@
+use chrono::{DateTime, Utc};
+use log::warn;
 use rust_decimal::Decimal;
+use std::time::Duration;
 
 #[derive(thiserror::Error, Debug)]
 pub enum YahooError {
@@ -12,19 +15,126 @@ pub enum YahooError {
     MalformedJson(#[from] serde_json::Error),
 }
 
+/// Retries a GET up to `retries` times with doubling backoff on timeout, connection error,
+/// HTTP 429, or HTTP 5xx, honoring `Retry-After` when Yahoo sends one. `build` is re-invoked
+/// on every attempt since a `RequestBuilder` is consumed by `send`.
+fn send_with_retry<F>(
+    client: &mut reqwest::blocking::Client,
+    label: &str,
+    build: F,
+    retries: u32,
+) -> Result<reqwest::blocking::Response, YahooError>
+where
+    F: Fn(&reqwest::blocking::Client) -> reqwest::blocking::RequestBuilder,
+{
+    let mut backoff = Duration::from_millis(500);
+    let mut attempt = 0;
+    loop {
+        match build(client)
+            .header("Accept", "application/json")
+            .send()
+        {
+            Ok(response) => {
+                let status = response.status();
+                if status.is_success() || status == reqwest::StatusCode::NOT_MODIFIED {
+                    return Ok(response);
+                }
+                if attempt < retries
+                    && (status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error())
+                {
+                    let wait = retry_after(&response).unwrap_or(backoff);
+                    warn!(
+                        "yahoo responded with {} for `{}`, retrying in {}ms ({}/{})",
+                        status,
+                        label,
+                        wait.as_millis(),
+                        attempt + 1,
+                        retries
+                    );
+                    std::thread::sleep(wait);
+                    backoff *= 2;
+                    attempt += 1;
+                    continue;
+                }
+                return Err(YahooError::HttpError(status));
+            }
+            Err(err) if attempt < retries && (err.is_timeout() || err.is_connect()) => {
+                warn!(
+                    "request for `{}` failed, retrying in {}ms ({}/{}): {}",
+                    label,
+                    backoff.as_millis(),
+                    attempt + 1,
+                    retries,
+                    err
+                );
+                std::thread::sleep(backoff);
+                backoff *= 2;
+                attempt += 1;
+            }
+            Err(err) => return Err(YahooError::ConnectionError(err)),
+        }
+    }
+}
+
+fn retry_after(response: &reqwest::blocking::Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok())
+        .map(Duration::from_secs)
+}
+
+/// Fetches a quote, reusing `cached` directly if it's younger than `cache_ttl`. Otherwise sends
+/// a conditional request (`If-None-Match`/`If-Modified-Since`); a 304 reuses the cached `Quote`
+/// with a refreshed timestamp instead of re-parsing a body.
 pub fn request_quote(
     client: &mut reqwest::blocking::Client,
     ticker: &str,
-) -> Result<Quote, YahooError> {
-    let response = client
-        .get(format!("https://query1.finance.yahoo.com/v8/finance/chart/{ticker}?symbol={ticker}&interval=1m&range=1m", ticker = ticker))
-        .header("Accept", "application/json")
-        .send()?;
-
-    if !response.status().is_success() {
-        return Err(YahooError::HttpError(response.status()));
+    retries: u32,
+    cached: Option<CachedQuote>,
+    cache_ttl: Duration,
+) -> Result<CachedQuote, YahooError> {
+    if let Some(cached) = &cached {
+        if is_fresh(cached, cache_ttl) {
+            return Ok(cached.clone());
+        }
     }
 
+    let url =
+        format!("https://query1.finance.yahoo.com/v8/finance/chart/{ticker}?symbol={ticker}&interval=1m&range=1m", ticker = ticker);
+    let response = send_with_retry(
+        client,
+        ticker,
+        |client| {
+            let mut request = client.get(&url);
+            if let Some(cached) = &cached {
+                if let Some(etag) = &cached.etag {
+                    request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+                }
+                if let Some(last_modified) = &cached.last_modified {
+                    request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+                }
+            }
+            request
+        },
+        retries,
+    )?;
+
+    if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+        return cached
+            .map(|cached| CachedQuote {
+                fetched_at: unix_time(),
+                ..cached
+            })
+            .ok_or(YahooError::InvalidResponse(
+                "Received 304 Not Modified with no cached quote",
+            ));
+    }
+
+    let etag = header_to_string(&response, reqwest::header::ETAG);
+    let last_modified = header_to_string(&response, reqwest::header::LAST_MODIFIED);
+
     let string = response.text().unwrap();
     let mut response: YahooResponse = serde_json::from_str(&string)?;
     if response.chart.result.is_empty() {
@@ -34,14 +144,102 @@ pub fn request_quote(
 
     assert_eq!(ticker, quote.symbol.as_str());
 
-    Ok(Quote {
-        price: quote.regular_market_price,
-        change: quote.regular_market_price
-            - quote.previous_close.or(quote.chart_previous_close).unwrap(),
-        currency: quote.currency,
+    Ok(CachedQuote {
+        etag,
+        last_modified,
+        fetched_at: unix_time(),
+        quote: Quote {
+            price: quote.regular_market_price,
+            change: quote.regular_market_price
+                - quote.previous_close.or(quote.chart_previous_close).unwrap(),
+            currency: quote.currency,
+        },
     })
 }
 
+fn header_to_string(response: &reqwest::blocking::Response, name: reqwest::header::HeaderName) -> Option<String> {
+    response
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+}
+
+/// Whether a cached quote is still within `cache_ttl` and doesn't need revalidating.
+pub fn is_fresh(cached: &CachedQuote, cache_ttl: Duration) -> bool {
+    unix_time().saturating_sub(cached.fetched_at) < cache_ttl.as_secs()
+}
+
+fn unix_time() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Fetches OHLCV candles for a ticker, e.g. `request_history(client, "GOOG", "1d", "1mo", 3)`.
+/// Rows with a `null` open/high/low/close are skipped.
+pub fn request_history(
+    client: &mut reqwest::blocking::Client,
+    ticker: &str,
+    interval: &str,
+    range: &str,
+    retries: u32,
+) -> Result<Vec<Candle>, YahooError> {
+    let url = format!("https://query1.finance.yahoo.com/v8/finance/chart/{ticker}?symbol={ticker}&interval={interval}&range={range}", ticker = ticker, interval = interval, range = range);
+    let response = send_with_retry(client, ticker, |client| client.get(&url), retries)?;
+
+    let string = response.text().unwrap();
+    let mut response: YahooResponse = serde_json::from_str(&string)?;
+    if response.chart.result.is_empty() {
+        return Err(YahooError::InvalidResponse("No data provided"));
+    }
+    let result = response.chart.result.remove(0);
+
+    let timestamp = result
+        .timestamp
+        .ok_or(YahooError::InvalidResponse("Missing timestamps"))?;
+    let indicators = result
+        .indicators
+        .quote
+        .into_iter()
+        .next()
+        .ok_or(YahooError::InvalidResponse("Missing OHLCV indicators"))?;
+    let adjclose = result
+        .indicators
+        .adjclose
+        .into_iter()
+        .next()
+        .map(|adjclose| adjclose.adjclose);
+
+    let mut candles = Vec::with_capacity(timestamp.len());
+    for i in 0..timestamp.len() {
+        let (Some(open), Some(high), Some(low), Some(close)) = (
+            indicators.open.get(i).copied().flatten(),
+            indicators.high.get(i).copied().flatten(),
+            indicators.low.get(i).copied().flatten(),
+            indicators.close.get(i).copied().flatten(),
+        ) else {
+            continue;
+        };
+        let volume = indicators.volume.get(i).copied().flatten().unwrap_or(0);
+        let adjclose = adjclose.as_ref().and_then(|a| a.get(i).copied().flatten());
+
+        candles.push(Candle {
+            timestamp: DateTime::from_timestamp(timestamp[i], 0)
+                .ok_or(YahooError::InvalidResponse("Invalid timestamp"))?,
+            open,
+            high,
+            low,
+            close,
+            adjclose,
+            volume,
+        });
+    }
+
+    Ok(candles)
+}
+
 #[derive(serde::Deserialize)]
 struct YahooResponse {
     chart: YahooChart,
@@ -53,8 +251,40 @@ struct YahooChart {
 }
 
 #[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct YahooQuote {
     meta: YahooMeta,
+    timestamp: Option<Vec<i64>>,
+    #[serde(default)]
+    indicators: YahooIndicators,
+}
+
+#[derive(serde::Deserialize, Default)]
+struct YahooIndicators {
+    #[serde(default)]
+    quote: Vec<YahooOhlcv>,
+    #[serde(default)]
+    adjclose: Vec<YahooAdjClose>,
+}
+
+#[derive(serde::Deserialize, Default)]
+struct YahooOhlcv {
+    #[serde(default)]
+    open: Vec<Option<Decimal>>,
+    #[serde(default)]
+    high: Vec<Option<Decimal>>,
+    #[serde(default)]
+    low: Vec<Option<Decimal>>,
+    #[serde(default)]
+    close: Vec<Option<Decimal>>,
+    #[serde(default)]
+    volume: Vec<Option<u64>>,
+}
+
+#[derive(serde::Deserialize, Default)]
+struct YahooAdjClose {
+    #[serde(default)]
+    adjclose: Vec<Option<Decimal>>,
 }
 
 #[derive(serde::Deserialize)]
@@ -67,8 +297,30 @@ struct YahooMeta {
     regular_market_price: Decimal,
 }
 
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
 pub struct Quote {
     pub price: Decimal,
     pub change: Decimal,
     pub currency: String,
 }
+
+/// A cached `Quote` plus the conditional-request metadata needed to revalidate it.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct CachedQuote {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// Unix timestamp (seconds) of the last successful fetch or revalidation.
+    pub fetched_at: u64,
+    pub quote: Quote,
+}
+
+/// A single OHLCV data point from `chart.result[0].indicators`.
+pub struct Candle {
+    pub timestamp: DateTime<Utc>,
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub close: Decimal,
+    pub adjclose: Option<Decimal>,
+    pub volume: u64,
+}